@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-target constants and ELF-class abstractions needed to walk dynamic
+//! relocations.
+//!
+//! `inner_dyn_reloc` needs two things that vary by target: which relocation
+//! type means "relative to the load base" (`R_RELATIVE`), and the ELF class
+//! of the `Dyn`/`Rel`/`Rela` structs it should read (`class`). The
+//! relative (`REL`/`RELA`/`RELR`) passes are otherwise the same across
+//! classes; the symbol-table (`GLOB_DAT`/`JUMP_SLOT`/`64`) and `IRELATIVE`
+//! passes are compiled for `x86_64` only.
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) use goblin::elf::reloc::R_X86_64_RELATIVE as R_RELATIVE;
+
+/// `R_AARCH64_RELATIVE`, see the ELF for the ARM 64-bit Architecture spec.
+#[cfg(target_arch = "aarch64")]
+pub(crate) const R_RELATIVE: u32 = 1027;
+
+/// `R_ARM_RELATIVE`, see the ELF for the ARM Architecture spec.
+#[cfg(target_arch = "arm")]
+pub(crate) const R_RELATIVE: u32 = 23;
+
+/// `R_386_RELATIVE`, see the System V ABI i386 supplement.
+#[cfg(target_arch = "x86")]
+pub(crate) const R_RELATIVE: u32 = 8;
+
+/// The ELF class (32- or 64-bit) `inner_dyn_reloc` is built for.
+///
+/// `x86_64`/`aarch64` binaries are class-64; `x86` (i686) and 32-bit `arm`
+/// are class-32, selected below by `target_pointer_width`.
+#[cfg(target_pointer_width = "64")]
+pub(crate) mod class {
+    pub(crate) use goblin::elf64::header::Header;
+    pub(crate) use goblin::elf64::program_header::{ProgramHeader, PT_DYNAMIC};
+    pub(crate) use goblin::elf::dynamic::dyn64::Dyn;
+    pub(crate) use goblin::elf::reloc::reloc64::{Rel, Rela};
+    pub(crate) use goblin::elf::sym::sym64::Sym;
+
+    /// Natural word size of this class; `DT_RELR` entries are this wide.
+    pub(crate) type Word = u64;
+
+    /// Mask applied to `r_info` to recover the relocation type.
+    pub(crate) const R_TYPE_MASK: u64 = 0x7fff_ffff;
+
+    /// Widens a `Word`-sized field (`d_tag`, `d_val`, `r_offset`, `r_info`, a
+    /// `DT_RELR` entry, `p_vaddr`, `e_phoff`, ...) to `u64` for arithmetic
+    /// that's shared across classes. A no-op on this (already 64-bit) class.
+    #[inline(always)]
+    pub(crate) fn to_u64(v: Word) -> u64 {
+        v
+    }
+
+    /// The inverse of [`to_u64`]; a no-op on this class.
+    #[inline(always)]
+    pub(crate) fn to_word(v: u64) -> Word {
+        v
+    }
+
+    /// Widens a `r_addend`-sized field to `i64`. A no-op on this class.
+    #[inline(always)]
+    pub(crate) fn addend_to_i64(v: i64) -> i64 {
+        v
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+pub(crate) mod class {
+    pub(crate) use goblin::elf32::header::Header;
+    pub(crate) use goblin::elf32::program_header::{ProgramHeader, PT_DYNAMIC};
+    pub(crate) use goblin::elf::dynamic::dyn32::Dyn;
+    pub(crate) use goblin::elf::reloc::reloc32::{Rel, Rela};
+    pub(crate) use goblin::elf::sym::sym32::Sym;
+
+    pub(crate) type Word = u32;
+
+    /// `ELF32_R_TYPE` is the low byte of `r_info`.
+    pub(crate) const R_TYPE_MASK: u64 = 0xff;
+
+    /// Widens a `Word`-sized field (`d_tag`, `d_val`, `r_offset`, `r_info`, a
+    /// `DT_RELR` entry, `p_vaddr`, `e_phoff`, ...) to `u64` for arithmetic
+    /// that's shared across classes.
+    #[inline(always)]
+    pub(crate) fn to_u64(v: Word) -> u64 {
+        v as u64
+    }
+
+    /// The inverse of [`to_u64`]; truncates back down to this class's width.
+    #[inline(always)]
+    pub(crate) fn to_word(v: u64) -> Word {
+        v as Word
+    }
+
+    /// Widens a `r_addend`-sized field to `i64`.
+    #[inline(always)]
+    pub(crate) fn addend_to_i64(v: i32) -> i64 {
+        v as i64
+    }
+}
@@ -5,13 +5,26 @@
 #![deny(clippy::all)]
 #![deny(missing_docs)]
 
-const R_TYPE_MASK: u64 = 0x7fffffff;
+mod arch;
 
-use goblin::elf::dynamic::dyn64::Dyn;
 use goblin::elf::dynamic::{DT_REL, DT_RELA, DT_RELASZ, DT_RELSZ};
-use goblin::elf::reloc::reloc64::Rel;
-use goblin::elf::reloc::reloc64::Rela;
-use goblin::elf::reloc::R_X86_64_RELATIVE;
+
+use arch::class::{addend_to_i64, to_u64, to_word, Dyn, Rel, Rela, Word, R_TYPE_MASK};
+use arch::R_RELATIVE;
+
+#[cfg(target_arch = "x86_64")]
+use goblin::elf::dynamic::{DT_SYMENT, DT_SYMTAB};
+#[cfg(target_arch = "x86_64")]
+use goblin::elf::reloc::{R_X86_64_64, R_X86_64_GLOB_DAT, R_X86_64_IRELATIVE, R_X86_64_JUMP_SLOT};
+#[cfg(target_arch = "x86_64")]
+use goblin::elf::section_header::SHN_UNDEF;
+#[cfg(target_arch = "x86_64")]
+use arch::class::Sym;
+
+// `DT_RELR`/`DT_RELRSZ` are not yet exposed by the version of `goblin` this
+// crate targets; the tag values themselves are fixed by the ELF gABI.
+const DT_RELR: u64 = 36;
+const DT_RELRSZ: u64 = 35;
 
 /// Dynamic relocation for a static PIE
 ///
@@ -39,29 +52,57 @@ unsafe fn inner_dyn_reloc(dynamic_section: *const u64, base: u64) {
     let mut dt_relsz: usize = 0;
     let mut dt_rela: Option<u64> = None;
     let mut dt_relasz: usize = 0;
+    let mut dt_relr: Option<u64> = None;
+    let mut dt_relrsz: usize = 0;
+    #[cfg(target_arch = "x86_64")]
+    let mut dt_symtab: Option<u64> = None;
+    #[cfg(target_arch = "x86_64")]
+    let mut dt_syment: usize = core::mem::size_of::<Sym>();
 
     let mut dynv = dynamic_section as *const Dyn;
 
     loop {
-        match (*dynv).d_tag {
+        match to_u64((*dynv).d_tag) {
             0 => break,
-            DT_REL => dt_rel = Some((*dynv).d_val),
+            DT_REL => dt_rel = Some(to_u64((*dynv).d_val)),
             DT_RELSZ => dt_relsz = (*dynv).d_val as usize / core::mem::size_of::<Rel>(),
-            DT_RELA => dt_rela = Some((*dynv).d_val),
+            DT_RELA => dt_rela = Some(to_u64((*dynv).d_val)),
             DT_RELASZ => dt_relasz = (*dynv).d_val as usize / core::mem::size_of::<Rela>(),
+            DT_RELR => dt_relr = Some(to_u64((*dynv).d_val)),
+            DT_RELRSZ => dt_relrsz = (*dynv).d_val as usize / core::mem::size_of::<Word>(),
+            #[cfg(target_arch = "x86_64")]
+            DT_SYMTAB => dt_symtab = Some(to_u64((*dynv).d_val)),
+            #[cfg(target_arch = "x86_64")]
+            DT_SYMENT => dt_syment = (*dynv).d_val as usize,
             _ => {}
         }
         dynv = dynv.add(1);
     }
 
+    // Resolves the symbol at `r_info >> 32` in `DT_SYMTAB` and returns its
+    // relocated value, if it is defined in this binary.
+    #[cfg(target_arch = "x86_64")]
+    let resolve_sym = |r_info: u64| -> Option<u64> {
+        let dt_symtab = dt_symtab?;
+        let sym_index = (r_info >> 32) as usize;
+        let sym = (base as usize + dt_symtab as usize + sym_index * dt_syment) as *const Sym;
+        unsafe {
+            if (*sym).st_shndx == SHN_UNDEF as u16 {
+                None
+            } else {
+                Some(base + (*sym).st_value)
+            }
+        }
+    };
+
     if let Some(dt_rel) = dt_rel {
         let rels = core::slice::from_raw_parts((base + dt_rel) as *const Rel, dt_relsz);
 
         rels.iter()
-            .filter(|rel| rel.r_info & R_TYPE_MASK == R_X86_64_RELATIVE as u64)
+            .filter(|rel| to_u64(rel.r_info) & R_TYPE_MASK == R_RELATIVE as u64)
             .for_each(|rel| {
-                let rel_addr = (base + rel.r_offset) as *mut u64;
-                rel_addr.write(rel_addr.read() + base);
+                let rel_addr = (base + to_u64(rel.r_offset)) as *mut Word;
+                rel_addr.write(to_word(to_u64(rel_addr.read()) + base));
             });
     }
 
@@ -70,10 +111,108 @@ unsafe fn inner_dyn_reloc(dynamic_section: *const u64, base: u64) {
 
         relas
             .iter()
-            .filter(|rela| rela.r_info & R_TYPE_MASK == R_X86_64_RELATIVE as u64)
+            .filter(|rela| to_u64(rela.r_info) & R_TYPE_MASK == R_RELATIVE as u64)
             .for_each(|rela| {
-                let rel_addr_0 = (base + rela.r_offset) as *mut u64;
-                rel_addr_0.write((base as i64 + rela.r_addend) as u64);
+                let rel_addr_0 = (base + to_u64(rela.r_offset)) as *mut Word;
+                rel_addr_0.write(to_word((base as i64 + addend_to_i64(rela.r_addend)) as u64));
+            });
+    }
+
+    if let Some(dt_relr) = dt_relr {
+        let relr = core::slice::from_raw_parts((base + dt_relr) as *const Word, dt_relrsz);
+
+        // Each entry carries this many payload bits in its bitmap form; see below.
+        let payload_bits = core::mem::size_of::<Word>() * 8 - 1;
+
+        let mut where_: *mut Word = core::ptr::null_mut();
+        for entry in relr {
+            let entry = to_u64(*entry);
+            if entry & 1 == 0 {
+                // An even entry is the address of the next word to relocate.
+                let addr = (base + entry) as *mut Word;
+                addr.write(to_word(to_u64(addr.read()) + base));
+                where_ = addr.add(1);
+            } else {
+                // An odd entry is a bitmap: bit `i` (the `payload_bits` bits
+                // above the marker bit) says whether `where_ + i` needs
+                // relocating.
+                let mut bits = entry >> 1;
+                let mut i = 0;
+                while bits != 0 {
+                    if bits & 1 != 0 {
+                        let addr = where_.add(i);
+                        addr.write(to_word(to_u64(addr.read()) + base));
+                    }
+                    bits >>= 1;
+                    i += 1;
+                }
+                where_ = where_.add(payload_bits);
+            }
+        }
+    }
+
+    // GLOB_DAT/JUMP_SLOT/64 relocations reference a symbol defined somewhere
+    // in the binary itself; undefined symbols are left untouched.
+    #[cfg(target_arch = "x86_64")]
+    if let Some(dt_rel) = dt_rel {
+        let rels = core::slice::from_raw_parts((base + dt_rel) as *const Rel, dt_relsz);
+
+        rels.iter()
+            .filter(|rel| {
+                matches!(
+                    rel.r_info & R_TYPE_MASK,
+                    t if t == R_X86_64_GLOB_DAT as u64 || t == R_X86_64_JUMP_SLOT as u64
+                )
+            })
+            .for_each(|rel| {
+                if let Some(value) = resolve_sym(rel.r_info) {
+                    let rel_addr = (base + rel.r_offset) as *mut u64;
+                    rel_addr.write(value);
+                }
+            });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    if let Some(dt_rela) = dt_rela {
+        let relas = core::slice::from_raw_parts((base + dt_rela) as *const Rela, dt_relasz);
+
+        relas
+            .iter()
+            .filter_map(|rela| {
+                // Per the x86-64 psABI only `R_X86_64_64` is `S + A`;
+                // `GLOB_DAT`/`JUMP_SLOT` are `S` with no addend term.
+                match rela.r_info & R_TYPE_MASK {
+                    t if t == R_X86_64_GLOB_DAT as u64 || t == R_X86_64_JUMP_SLOT as u64 => {
+                        Some((rela, resolve_sym(rela.r_info)?))
+                    }
+                    t if t == R_X86_64_64 as u64 => Some((
+                        rela,
+                        (resolve_sym(rela.r_info)? as i64 + rela.r_addend) as u64,
+                    )),
+                    _ => None,
+                }
+            })
+            .for_each(|(rela, value)| {
+                let rel_addr = (base + rela.r_offset) as *mut u64;
+                rel_addr.write(value);
+            });
+    }
+
+    // IRELATIVE resolvers may read globals fixed up above, so this pass has
+    // to run last.
+    #[cfg(target_arch = "x86_64")]
+    if let Some(dt_rela) = dt_rela {
+        let relas = core::slice::from_raw_parts((base + dt_rela) as *const Rela, dt_relasz);
+
+        relas
+            .iter()
+            .filter(|rela| rela.r_info & R_TYPE_MASK == R_X86_64_IRELATIVE as u64)
+            .for_each(|rela| {
+                let resolver = core::mem::transmute::<u64, extern "C" fn() -> u64>(
+                    (base as i64 + rela.r_addend) as u64,
+                );
+                let rel_addr = (base + rela.r_offset) as *mut u64;
+                rel_addr.write(resolver());
             });
     }
 }
@@ -100,7 +239,7 @@ pub unsafe extern "C" fn rcrt(
     sp: *const usize,
     pre_main: extern "C" fn() -> !,
 ) -> ! {
-    use goblin::elf64::program_header::{ProgramHeader, PT_DYNAMIC};
+    use arch::class::{ProgramHeader, PT_DYNAMIC};
     const AT_PHDR: usize = 3;
     const AT_PHENT: usize = 4;
     const AT_PHNUM: usize = 5;
@@ -141,7 +280,56 @@ pub unsafe extern "C" fn rcrt(
         // Search all ELF program headers for the `_DYNAMIC` section
         if (*ph).p_type == PT_DYNAMIC {
             // calculate the offset, where the elf binary got loaded
-            let base = dynv as u64 - (*ph).p_vaddr;
+            let base = dynv as u64 - arch::class::to_u64((*ph).p_vaddr);
+
+            inner_dyn_reloc(dynv, base);
+
+            // Now call the `pre_main()` function and never return
+            pre_main()
+        }
+        ph = (ph as usize + phentsize) as *const ProgramHeader;
+        i -= 1;
+    }
+
+    // Fail horribly, if we ever reach this point
+    unreachable!();
+}
+
+extern "C" {
+    // Linker-provided symbol marking the start of the ELF header; since the
+    // header is mapped at the load base, its address *is* the load base.
+    static __ehdr_start: u8;
+}
+
+/// Auxv-free variant of [`rcrt`]
+///
+/// Environments like SGX enclaves set up the initial stack themselves and don't
+/// populate a Linux-style auxv, so `AT_PHDR`/`AT_PHENT`/`AT_PHNUM` aren't
+/// available. Instead of reading them off the stack, this walks the program
+/// headers reachable from the linker-provided `__ehdr_start` symbol, which
+/// points at this binary's own (already mapped) ELF header.
+///
+/// # Safety
+///
+/// This function is unsafe, because the caller has to ensure `__ehdr_start` points
+/// at a valid, loaded ELF header.
+#[inline(never)]
+pub unsafe extern "C" fn rcrt_no_auxv(pre_main: extern "C" fn() -> !) -> ! {
+    use arch::class::{Header, ProgramHeader, PT_DYNAMIC};
+
+    let base = &__ehdr_start as *const u8 as u64;
+    let ehdr = base as *const Header;
+
+    let mut ph = (base + arch::class::to_u64((*ehdr).e_phoff)) as *const ProgramHeader;
+    let mut i = (*ehdr).e_phnum as usize;
+    let phentsize = (*ehdr).e_phentsize as usize;
+
+    while i != 0 {
+        // Search all ELF program headers for the `PT_DYNAMIC` section
+        if (*ph).p_type == PT_DYNAMIC {
+            // The header is always loaded at the base address, so `base` doubles
+            // as the offset applied to every `p_vaddr`.
+            let dynv = (base + arch::class::to_u64((*ph).p_vaddr)) as *const u64;
 
             inner_dyn_reloc(dynv, base);
 
@@ -213,3 +401,150 @@ macro_rules! x86_64_linux_startup {
         }
     };
 }
+
+/// AArch64 variant of [`x86_64_linux_startup!`]; see its docs for usage.
+#[macro_export]
+macro_rules! aarch64_linux_startup {
+    (fn $name:ident() -> ! $code:block ) => {
+        #[no_mangle]
+        #[naked]
+        pub unsafe extern "C" fn $name() -> ! {
+            use core::arch::asm;
+
+            fn inner() -> ! {
+                $code
+            }
+
+            // Call `rcrt1::rcrt` with the absolute address of the `_DYNAMIC` section
+            // and the stack pointer and our `pre_main` function
+            asm!(
+                "adrp   x0, _DYNAMIC",
+                "add    x0, x0, #:lo12:_DYNAMIC",
+                "mov    x1, sp",
+                "adrp   x2, {INNER}",
+                "add    x2, x2, #:lo12:{INNER}",
+                "b      {RCRT}",
+
+                RCRT = sym $crate::rcrt,
+                INNER = sym inner,
+                options(noreturn)
+            )
+        }
+    };
+}
+
+/// 32-bit ARM variant of [`x86_64_linux_startup!`]; see its docs for usage.
+#[macro_export]
+macro_rules! arm_linux_startup {
+    (fn $name:ident() -> ! $code:block ) => {
+        #[no_mangle]
+        #[naked]
+        pub unsafe extern "C" fn $name() -> ! {
+            use core::arch::asm;
+
+            fn inner() -> ! {
+                $code
+            }
+
+            // 32-bit ARM has no single-instruction large-range PC-relative
+            // addressing, so `_DYNAMIC`/`inner`'s runtime addresses are
+            // recovered via `adr` (PC-relative address of a nearby literal)
+            // plus a link-time symbol-difference constant stored in that
+            // literal; a plain `ldr reg, =symbol` would load the unrelocated
+            // link address, which is wrong for any non-zero load base.
+            asm!(
+                "mov    r3, sp",
+                "adr    r0, 1f",
+                "ldr    r1, [r0]",
+                "add    r0, r0, r1",
+                "adr    r2, 2f",
+                "ldr    r1, [r2]",
+                "add    r2, r2, r1",
+                "mov    r1, r3",
+                "b      3f",
+                "1: .word _DYNAMIC - 1b",
+                "2: .word {INNER} - 2b",
+                "3:",
+                "b      {RCRT}",
+
+                RCRT = sym $crate::rcrt,
+                INNER = sym inner,
+                options(noreturn)
+            )
+        }
+    };
+}
+
+/// Variant of [`x86_64_linux_startup!`] for environments without a Linux-style
+/// auxv, such as SGX enclaves. Calls [`rcrt_no_auxv`] instead of [`rcrt`], so no
+/// stack pointer needs to be passed in.
+#[macro_export]
+macro_rules! x86_64_linux_startup_no_auxv {
+    (fn $name:ident() -> ! $code:block ) => {
+        #[no_mangle]
+        #[naked]
+        pub unsafe extern "sysv64" fn $name() -> ! {
+            use core::arch::asm;
+
+            fn inner() -> ! {
+                $code
+            }
+
+            // Call `rcrt1::rcrt_no_auxv` with our `pre_main` function; unlike
+            // `x86_64_linux_startup!` this needs neither `_DYNAMIC` nor `rsp`.
+            asm!(
+                "lea    rdi, [rip + {INNER}]",
+                "jmp    {RCRT}",
+
+                RCRT = sym $crate::rcrt_no_auxv,
+                INNER = sym inner,
+                options(noreturn)
+            )
+        }
+    };
+}
+
+/// 32-bit x86 (i686) variant of [`x86_64_linux_startup!`]; see its docs for usage.
+#[macro_export]
+macro_rules! i686_linux_startup {
+    (fn $name:ident() -> ! $code:block ) => {
+        #[no_mangle]
+        #[naked]
+        pub unsafe extern "cdecl" fn $name() -> ! {
+            use core::arch::asm;
+
+            fn inner() -> ! {
+                $code
+            }
+
+            // x86-32 has no RIP-relative addressing, so `_DYNAMIC`/`inner`'s
+            // runtime addresses are recovered the way musl's i386
+            // crt_arch.h does: `call`+`pop` to read the current PC into a
+            // register, then add the link-time displacement to the target
+            // symbol, which is a constant since both live in the same
+            // loaded image. A plain `lea eax, [_DYNAMIC]` would instead
+            // encode the unrelocated link address, which is wrong for any
+            // non-zero load base.
+            //
+            // `rcrt1::rcrt` then takes its arguments cdecl-style, pushed
+            // right to left: the `_DYNAMIC` address, the (unmodified) stack
+            // pointer, and our `pre_main` function.
+            asm!(
+                "mov    ecx, esp",
+                "call   2f",
+                "2:",
+                "pop    ebx",
+                "lea    edx, [ebx + (_DYNAMIC - 2b)]",
+                "lea    eax, [ebx + ({INNER} - 2b)]",
+                "push   eax",
+                "push   ecx",
+                "push   edx",
+                "call   {RCRT}",
+
+                RCRT = sym $crate::rcrt,
+                INNER = sym inner,
+                options(noreturn)
+            )
+        }
+    };
+}